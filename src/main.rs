@@ -1,8 +1,12 @@
 use crossbeam::channel::{Receiver, Sender, bounded};
 use nix::{
     fcntl::{OFlag, open},
+    libc,
     sys::{
-        fanotify::{EventFFlags, Fanotify, FanotifyEvent, InitFlags, MarkFlags, MaskFlags},
+        fanotify::{
+            EventFFlags, Fanotify, FanotifyEvent, FanotifyResponse, InitFlags, MarkFlags,
+            MaskFlags, Response,
+        },
         stat::Mode,
     },
 };
@@ -12,17 +16,194 @@ use std::{
 };
 use std::{
     fs,
-    os::unix::io::AsRawFd,
+    mem,
+    os::unix::io::{AsRawFd, OwnedFd, RawFd},
     path::{Path, PathBuf},
+    sync::Arc,
     thread,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-// Acceptable file systems for monitoring TODO: make it configurable
+// Default allowlist of file systems to auto-discover; overridable at runtime.
 const ACCEPTED_FS: &[&str] = &["ext4", "xfs", "btrfs", "vfat"];
 
-/// Discover all monitored mount points from /proc/mounts
-fn monitored_mounts() -> Vec<(String, String)> {
+/// How resolved events are rendered on stdout.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// `name(pid): CODE /path` — the original human-readable line.
+    Human,
+    /// One JSON object per line, suitable for later analysis.
+    Json,
+}
+
+/// Runtime configuration assembled from the command line.
+struct Config {
+    /// Explicit paths to mark; when empty, all accepted mounts are discovered.
+    paths: Vec<String>,
+    /// File-system allowlist used during mount discovery.
+    accepted_fs: Vec<String>,
+    /// Output rendering.
+    format: OutputFormat,
+    /// Tracing mode; `Policy` blocks access according to `rules`.
+    mode: TraceMode,
+    /// Access-control rules, consulted only in `Policy` mode.
+    rules: Vec<Rule>,
+    /// Paths to add persistent ignore marks on, suppressing their events.
+    exclude: Vec<String>,
+}
+
+impl Config {
+    /// Parse `args` (excluding the program name). Unknown flags abort with a
+    /// usage message so mistakes are not silently ignored.
+    fn parse<I: Iterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut paths = Vec::new();
+        let mut accepted_fs: Vec<String> = ACCEPTED_FS.iter().map(|s| s.to_string()).collect();
+        let mut format = OutputFormat::Human;
+        let mut mode = TraceMode::Notify;
+        let mut rules = Vec::new();
+        let mut exclude = Vec::new();
+
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--json" => format = OutputFormat::Json,
+                // Replace the allowlist with a comma-separated list.
+                "--fs" => {
+                    let list = args.next().ok_or("--fs requires a comma-separated list")?;
+                    accepted_fs = list.split(',').map(|s| s.trim().to_string()).collect();
+                }
+                // Append one or more types to the allowlist.
+                "--fs-add" => {
+                    let list = args.next().ok_or("--fs-add requires a comma-separated list")?;
+                    accepted_fs.extend(list.split(',').map(|s| s.trim().to_string()));
+                }
+                // Switch to blocking access-control mode.
+                "--policy" => mode = TraceMode::Policy,
+                // Add an allow/deny rule; specs select Policy mode implicitly.
+                "--allow" => {
+                    let spec = args.next().ok_or("--allow requires a GLOB[@PROC] spec")?;
+                    rules.push(parse_rule(&spec, Verdict::Allow));
+                    mode = TraceMode::Policy;
+                }
+                "--deny" => {
+                    let spec = args.next().ok_or("--deny requires a GLOB[@PROC] spec")?;
+                    rules.push(parse_rule(&spec, Verdict::Deny));
+                    mode = TraceMode::Policy;
+                }
+                // Suppress events on a path; may be given more than once.
+                "--exclude" => {
+                    let path = args.next().ok_or("--exclude requires a PATH")?;
+                    exclude.push(path);
+                }
+                other if other.starts_with('-') => {
+                    return Err(format!("unknown option: {other}"));
+                }
+                _ => paths.push(arg),
+            }
+        }
+
+        Ok(Self {
+            paths,
+            accepted_fs,
+            format,
+            mode,
+            rules,
+            exclude,
+        })
+    }
+}
+
+/// Tracing mode: purely observational, or a blocking access-control policy.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TraceMode {
+    /// `FAN_CLASS_NOTIF` — events are reported after the fact.
+    Notify,
+    /// `FAN_CLASS_CONTENT` — permission events must be answered allow/deny.
+    Policy,
+}
+
+/// Allow or deny verdict for a permission event.
+#[derive(Clone, Copy)]
+enum Verdict {
+    Allow,
+    Deny,
+}
+
+/// A single access-control rule: a path glob, an optional process-name match
+/// and the verdict to apply when both match.
+struct Rule {
+    path_glob: String,
+    proc_name: Option<String>,
+    verdict: Verdict,
+}
+
+/// Ordered set of rules; the first matching rule wins, defaulting to `Allow`
+/// when nothing matches so that an empty policy never blocks anything.
+struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    fn new(rules: Vec<Rule>) -> Self {
+        Self { rules }
+    }
+
+    /// Evaluate a (path, process name) pair against the rule set.
+    fn evaluate(&self, path: &str, proc_name: &str) -> Verdict {
+        for rule in &self.rules {
+            if let Some(name) = &rule.proc_name
+                && name != proc_name
+            {
+                continue;
+            }
+            if glob_match(&rule.path_glob, path) {
+                return rule.verdict;
+            }
+        }
+        Verdict::Allow
+    }
+}
+
+/// Parse a rule spec of the form `GLOB` or `GLOB@PROCNAME` into a [`Rule`]
+/// with the given verdict. The optional `@PROCNAME` suffix restricts the rule
+/// to a single process name.
+fn parse_rule(spec: &str, verdict: Verdict) -> Rule {
+    match spec.split_once('@') {
+        Some((glob, proc_name)) => Rule {
+            path_glob: glob.to_string(),
+            proc_name: Some(proc_name.to_string()),
+            verdict,
+        },
+        None => Rule {
+            path_glob: spec.to_string(),
+            proc_name: None,
+            verdict,
+        },
+    }
+}
+
+/// Minimal glob matcher supporting `*` as a wildcard for any run of
+/// characters. Enough to match path prefixes such as `/etc/*` without pulling
+/// in an external dependency.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((head, tail)) => {
+            if !text.starts_with(head) {
+                return false;
+            }
+            let rest = &text[head.len()..];
+            // Recurse so multiple `*` segments are handled left to right.
+            (0..=rest.len())
+                .filter(|&i| rest.is_char_boundary(i))
+                .any(|i| glob_match(tail, &rest[i..]))
+        }
+    }
+}
+
+/// Discover all monitored mount points from /proc/mounts whose file-system
+/// type is in `accepted_fs`.
+fn monitored_mounts(accepted_fs: &[String]) -> Vec<(String, String)> {
     let mut mounts = Vec::new();
     if let Ok(content) = fs::read_to_string("/proc/mounts") {
         for line in content.lines() {
@@ -32,7 +213,7 @@ fn monitored_mounts() -> Vec<(String, String)> {
                 let mountpoint = fields[1].to_string();
                 let fstype = fields[2];
 
-                if ACCEPTED_FS.contains(&fstype) {
+                if accepted_fs.iter().any(|fs| fs == fstype) {
                     mounts.push((device, mountpoint));
                 }
             }
@@ -62,16 +243,24 @@ fn mask_to_code(mask: MaskFlags) -> String {
     use MaskFlags as MF;
     let mut s = String::new();
 
-    // TODO: put all mask flags
-    if mask.contains(MF::FAN_OPEN) {
+    // An exec-open is a more specific form of open, so report it as its own
+    // code ('E') rather than a plain 'O'.
+    if mask.intersects(MF::FAN_OPEN_EXEC | MF::FAN_OPEN_EXEC_PERM) {
+        s.push('E');
+    } else if mask.intersects(MF::FAN_OPEN | MF::FAN_OPEN_PERM) {
         s.push('O');
     }
-    if mask.contains(MF::FAN_ACCESS) {
+    if mask.intersects(MF::FAN_ACCESS | MF::FAN_ACCESS_PERM) {
         s.push('R');
     }
     if mask.contains(MF::FAN_MODIFY) {
         s.push('W');
     }
+    if mask.contains(MF::FAN_ATTRIB) {
+        s.push('A');
+    }
+    // FAN_CLOSE is the union of the write/nowrite close events; report each
+    // distinctly so a close-after-write is told apart from a read-only close.
     if mask.contains(MF::FAN_CLOSE_WRITE) {
         s.push('C');
     }
@@ -90,6 +279,10 @@ fn mask_to_code(mask: MaskFlags) -> String {
     if mask.contains(MF::FAN_MOVED_TO) {
         s.push('>');
     }
+    // A trailing '/' marks events that happened on a directory.
+    if mask.contains(MF::FAN_ONDIR) {
+        s.push('/');
+    }
 
     if s.is_empty() {
         s.push('?');
@@ -97,13 +290,91 @@ fn mask_to_code(mask: MaskFlags) -> String {
     s
 }
 
-/// Setup fanotify instance
-fn setup_fanotify() -> nix::Result<Fanotify> {
-    Fanotify::init(InitFlags::FAN_CLASS_NOTIF, EventFFlags::O_RDONLY)
+/// Setup fanotify instance for the given mode.
+///
+/// `Notify` opens a `FAN_CLASS_NOTIF` group (observational); `Policy` opens a
+/// `FAN_CLASS_CONTENT` group so that permission events can be answered.
+fn setup_fanotify(mode: TraceMode) -> nix::Result<Fanotify> {
+    let flags = match mode {
+        // FAN_REPORT_DFID_NAME implies FAN_REPORT_FID: directory-entry events
+        // (create/delete/move) carry the parent directory's file handle plus
+        // the entry name instead of an open fd. nix does not expose these
+        // report flags, so OR in the raw libc bits.
+        TraceMode::Notify => {
+            InitFlags::FAN_CLASS_NOTIF
+                | InitFlags::from_bits_retain(
+                    libc::FAN_REPORT_FID | libc::FAN_REPORT_DFID_NAME,
+                )
+        }
+        TraceMode::Policy => InitFlags::FAN_CLASS_CONTENT,
+    };
+    Fanotify::init(flags, EventFFlags::O_RDONLY)
+}
+
+/// Events that a **mount** mark can carry. These are the per-open/access
+/// events the kernel supports on a `FAN_MARK_MOUNT` mark; in `Policy` mode
+/// they are requested as their permission variants.
+fn mount_event_mask(mode: TraceMode) -> MaskFlags {
+    let events = match mode {
+        TraceMode::Notify => {
+            MaskFlags::FAN_OPEN
+                | MaskFlags::FAN_OPEN_EXEC
+                | MaskFlags::FAN_ACCESS
+                | MaskFlags::FAN_MODIFY
+                | MaskFlags::FAN_CLOSE_WRITE
+                | MaskFlags::FAN_CLOSE_NOWRITE
+        }
+        TraceMode::Policy => {
+            MaskFlags::FAN_OPEN_PERM
+                | MaskFlags::FAN_OPEN_EXEC_PERM
+                | MaskFlags::FAN_ACCESS_PERM
+        }
+    };
+    events | MaskFlags::FAN_EVENT_ON_CHILD | MaskFlags::FAN_ONDIR
+}
+
+/// Directory-entry and inode events (create/delete/move/attrib). The kernel
+/// only delivers these through a filesystem (or inode) mark on a group opened
+/// with FID reporting — they are rejected on a mount mark — so they are marked
+/// separately from [`mount_event_mask`]. Only meaningful in `Notify` mode;
+/// `Policy` mode watches open/access permissions only and returns an empty set.
+fn filesystem_event_mask(mode: TraceMode) -> MaskFlags {
+    let events = match mode {
+        TraceMode::Notify => {
+            MaskFlags::FAN_ATTRIB
+                | MaskFlags::FAN_CREATE
+                | MaskFlags::FAN_DELETE
+                | MaskFlags::FAN_MOVED_FROM
+                | MaskFlags::FAN_MOVED_TO
+        }
+        TraceMode::Policy => MaskFlags::empty(),
+    };
+    if events.is_empty() {
+        events
+    } else {
+        events | MaskFlags::FAN_EVENT_ON_CHILD | MaskFlags::FAN_ONDIR
+    }
+}
+
+/// The full set of events watched for a mode, used by ignore marks so a single
+/// exclude suppresses everything the mount and filesystem marks would report.
+fn event_mask(mode: TraceMode) -> MaskFlags {
+    mount_event_mask(mode) | filesystem_event_mask(mode)
 }
 
-/// Add a fanotify mark to a given mount path
-fn mark_mount<P: AsRef<Path>>(fan: &Fanotify, mount_path: P) -> nix::Result<()> {
+/// Add a fanotify mark covering `mount_path`.
+///
+/// Two marks are laid down: a `FAN_MARK_MOUNT` mark for the per-open/access
+/// events the kernel allows on a mount (see [`mount_event_mask`]), and — in
+/// `Notify` mode — a `FAN_MARK_FILESYSTEM` mark for the directory-entry and
+/// inode events (create/delete/move/attrib), which the kernel only delivers
+/// through a filesystem or inode mark.
+///
+/// In `Policy` mode the open/access events are requested as their permission
+/// variants (`FAN_OPEN_PERM`/`FAN_ACCESS_PERM`) so the consumer can block the
+/// originating process until it replies allow or deny, and no filesystem mark
+/// is added.
+fn mark_mount<P: AsRef<Path>>(fan: &Fanotify, mount_path: P, mode: TraceMode) -> nix::Result<()> {
     let path = mount_path.as_ref();
 
     let dirfd = open(
@@ -112,35 +383,264 @@ fn mark_mount<P: AsRef<Path>>(fan: &Fanotify, mount_path: P) -> nix::Result<()>
         Mode::empty(),
     )?;
 
-    // let events = MaskFlags::FAN_OPEN
-    // | MaskFlags::FAN_ACCESS
-    // | MaskFlags::FAN_MODIFY
-    // | MaskFlags::FAN_CLOSE_WRITE
-    // | MaskFlags::FAN_CLOSE_NOWRITE
-    // | MaskFlags::FAN_EVENT_ON_CHILD
-    // | MaskFlags::FAN_CREATE
-    // | MaskFlags::FAN_DELETE
-    // | MaskFlags::FAN_MOVED_FROM
-    // | MaskFlags::FAN_MOVED_TO;
-
     fan.mark(
         MarkFlags::FAN_MARK_ADD | MarkFlags::FAN_MARK_MOUNT,
-        MaskFlags::FAN_OPEN | MaskFlags::FAN_ACCESS | MaskFlags::FAN_EVENT_ON_CHILD,
+        mount_event_mask(mode),
         &dirfd,
         Some(path),
+    )?;
+
+    let fs_events = filesystem_event_mask(mode);
+    if !fs_events.is_empty() {
+        fan.mark(
+            MarkFlags::FAN_MARK_ADD | MarkFlags::FAN_MARK_FILESYSTEM,
+            fs_events,
+            &dirfd,
+            Some(path),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Add a persistent ignore mark on a single inode so events on `path` are
+/// suppressed on top of the surrounding mount mark.
+///
+/// The ignore mask on an inode and the mask on the mount are merged by the
+/// kernel, so marking a path here removes it from the mount's event stream.
+/// `FAN_MARK_IGNORED_SURV_MODIFY` is always set: without it the kernel drops
+/// the ignore mask after the first modify event, which would let a busy file
+/// leak events again — a persistent ignore is almost always what the caller
+/// wants.
+fn mark_ignore<P: AsRef<Path>>(fan: &Fanotify, path: P, mode: TraceMode) -> nix::Result<()> {
+    let path = path.as_ref();
+
+    let fd = open(path, OFlag::O_PATH | OFlag::O_CLOEXEC, Mode::empty())?;
+
+    fan.mark(
+        MarkFlags::FAN_MARK_ADD
+            | MarkFlags::FAN_MARK_IGNORED_MASK
+            | MarkFlags::FAN_MARK_IGNORED_SURV_MODIFY,
+        event_mask(mode),
+        &fd,
+        Some(path),
+    )
+}
+
+/// True when the event is a permission request that must be answered.
+fn is_perm_event(mask: MaskFlags) -> bool {
+    mask.intersects(
+        MaskFlags::FAN_OPEN_PERM | MaskFlags::FAN_OPEN_EXEC_PERM | MaskFlags::FAN_ACCESS_PERM,
     )
 }
 
-/// Thread: continuously read events from fanotify and send via channel
-fn spawn_reader(fan: Fanotify, tx: Sender<FanotifyEvent>) {
+/// Reply to a permission event on the fanotify fd. Every permission event must
+/// be answered exactly once or the originating process blocks forever.
+fn respond(fan: &Fanotify, ev: &FanotifyEvent, verdict: Verdict) {
+    let response = match verdict {
+        Verdict::Allow => Response::FAN_ALLOW,
+        Verdict::Deny => Response::FAN_DENY,
+    };
+    match ev.fd() {
+        Some(fd) => {
+            if let Err(e) = fan.write_response(FanotifyResponse::new(fd, response)) {
+                eprintln!("failed to answer permission event: {e}");
+            }
+        }
+        // A permission event without an fd cannot be answered on this fd, which
+        // would block the originating process forever. This should not happen
+        // for FAN_*_PERM events, so log loudly rather than failing silently.
+        None => eprintln!("permission event carried no fd; cannot answer, process may block"),
+    }
+}
+
+/// A resolved event ready to be printed: process id, the raw event mask and
+/// the path it refers to (if it could be resolved). This is the single item
+/// type the consumer sees regardless of whether the path came from an open fd
+/// or from a directory file handle.
+struct TracedEvent {
+    pid: i32,
+    mask: MaskFlags,
+    path: Option<String>,
+    /// Wall-clock time the event was read; fanotify events carry no timestamp
+    /// of their own, so it is captured in the reader.
+    time: SystemTime,
+}
+
+/// Resolve a single `fanotify_event_info_fid` record to a path by opening the
+/// object (directory or file) it names with `open_by_handle_at` and joining the trailing entry
+/// name (present for `DFID_NAME` records). `mount_fds` are candidate fds on the
+/// monitored mounts; the handle only resolves against an fd on the same
+/// filesystem, so each is tried in turn.
+fn resolve_fid_record(record: &[u8], mount_fds: &[RawFd]) -> Option<String> {
+    // struct fanotify_event_info_fid { header (4 bytes); __kernel_fsid_t (8); file_handle; }
+    const HEADER_LEN: usize = 4;
+    const FSID_LEN: usize = 8;
+    let fh_off = HEADER_LEN + FSID_LEN;
+    if record.len() < fh_off + 8 {
+        return None;
+    }
+
+    // struct file_handle { __u32 handle_bytes; int handle_type; unsigned char f_handle[]; }
+    let handle_bytes = u32::from_ne_bytes(record[fh_off..fh_off + 4].try_into().ok()?) as usize;
+    let fh_len = 8 + handle_bytes;
+    if record.len() < fh_off + fh_len {
+        return None;
+    }
+
+    let handle_ptr = record[fh_off..].as_ptr() as *mut libc::file_handle;
+    let mut dir_fd = -1;
+    for &mfd in mount_fds {
+        // SAFETY: handle_ptr points at a well-formed file_handle within the
+        // record; open_by_handle_at reads handle_bytes past the header.
+        dir_fd = unsafe { libc::open_by_handle_at(mfd, handle_ptr, libc::O_PATH) };
+        if dir_fd >= 0 {
+            break;
+        }
+    }
+    if dir_fd < 0 {
+        return None;
+    }
+    let dir_path = fd_to_path(dir_fd).ok();
+    // SAFETY: dir_fd is a valid fd we obtained above and no longer use.
+    unsafe { libc::close(dir_fd) };
+    let mut path = dir_path?.display().to_string();
+
+    // Anything after the file handle is a NUL-terminated entry name.
+    let name_off = fh_off + fh_len;
+    if record.len() > name_off {
+        let name_bytes = &record[name_off..];
+        let end = name_bytes
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(name_bytes.len());
+        if let Ok(name) = std::str::from_utf8(&name_bytes[..end])
+            && !name.is_empty()
+            && name != "."
+        {
+            path.push('/');
+            path.push_str(name);
+        }
+    }
+    Some(path)
+}
+
+/// Walk the info records trailing an event's metadata and return the first
+/// file-id record (`FID`, `DFID` or `DFID_NAME`) that resolves to a path.
+fn fid_to_path(mut data: &[u8], mount_fds: &[RawFd]) -> Option<String> {
+    while data.len() >= 4 {
+        let info_type = data[0];
+        let len = u16::from_ne_bytes([data[2], data[3]]) as usize;
+        if len < 4 || len > data.len() {
+            break;
+        }
+        if matches!(
+            info_type,
+            libc::FAN_EVENT_INFO_TYPE_FID
+                | libc::FAN_EVENT_INFO_TYPE_DFID
+                | libc::FAN_EVENT_INFO_TYPE_DFID_NAME
+        ) && let Some(path) = resolve_fid_record(&data[..len], mount_fds)
+        {
+            return Some(path);
+        }
+        data = &data[len..];
+    }
+    None
+}
+
+/// Thread: read raw events from a `FAN_REPORT_FID` group, resolving each to a
+/// path either from its open fd or, for directory-entry events that carry no
+/// fd, from the file handle in the trailing info record.
+fn spawn_reader_notify(fan: Arc<Fanotify>, mount_fds: Vec<OwnedFd>, tx: Sender<TracedEvent>) {
+    thread::spawn(move || {
+        let fd = fan.as_raw_fd();
+        let raw_mounts: Vec<RawFd> = mount_fds.iter().map(|f| f.as_raw_fd()).collect();
+        let meta_len = mem::size_of::<libc::fanotify_event_metadata>();
+        let mut buf = [0u8; 8192];
+
+        loop {
+            // SAFETY: reading into a stack buffer of known length.
+            let n = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+            if n <= 0 {
+                eprintln!("fanotify read error");
+                thread::sleep(Duration::from_millis(250));
+                continue;
+            }
+            let n = n as usize;
+
+            let mut off = 0;
+            while off + meta_len <= n {
+                // SAFETY: off + meta_len <= n, so the metadata is in bounds.
+                let meta = unsafe {
+                    &*(buf[off..].as_ptr() as *const libc::fanotify_event_metadata)
+                };
+                let event_len = meta.event_len as usize;
+                if event_len < meta_len || off + event_len > n {
+                    break;
+                }
+
+                let mask = MaskFlags::from_bits_truncate(meta.mask);
+                let pid = meta.pid;
+                let path = if meta.fd != libc::FAN_NOFD {
+                    let resolved = fd_to_path(meta.fd).ok().map(|p| p.display().to_string());
+                    // SAFETY: the kernel handed us an owned fd; close it once.
+                    unsafe { libc::close(meta.fd) };
+                    resolved
+                } else {
+                    fid_to_path(&buf[off + meta_len..off + event_len], &raw_mounts)
+                };
+
+                let event = TracedEvent {
+                    pid,
+                    mask,
+                    path,
+                    time: SystemTime::now(),
+                };
+                if tx.send(event).is_err() {
+                    return;
+                }
+                off += event_len;
+            }
+        }
+    });
+}
+
+/// Thread: read permission events from a `FAN_CLASS_CONTENT` group, answer each
+/// exactly once, and forward the resolved event for printing.
+///
+/// A path that cannot be resolved (missing fd, failed lookup) falls back to
+/// `Allow`. An event that carries no fd at all cannot be answered; [`respond`]
+/// only logs it.
+fn spawn_reader_policy(fan: Arc<Fanotify>, tx: Sender<TracedEvent>, rules: RuleSet) {
     thread::spawn(move || {
         loop {
             match fan.read_events() {
                 Ok(events) => {
                     for ev in events {
-                        // send only if consumer is alive
-                        if tx.send(ev).is_err() {
-                            break;
+                        let pid = ev.pid();
+                        let name = pid_to_name(pid);
+                        let mask = ev.mask();
+                        let resolved = ev
+                            .fd()
+                            .and_then(|fd| fd_to_path(fd.as_raw_fd()).ok())
+                            .map(|p| p.display().to_string());
+
+                        if is_perm_event(mask) {
+                            let verdict = match &resolved {
+                                Some(path) => rules.evaluate(path, &name),
+                                None => Verdict::Allow,
+                            };
+                            respond(&fan, &ev, verdict);
+                        }
+
+                        let event = TracedEvent {
+                            pid,
+                            mask,
+                            path: resolved,
+                            time: SystemTime::now(),
+                        };
+                        if tx.send(event).is_err() {
+                            return;
                         }
                     }
                 }
@@ -153,43 +653,289 @@ fn spawn_reader(fan: Fanotify, tx: Sender<FanotifyEvent>) {
     });
 }
 
-/// Process fanotify events from channel
-fn process_events(rx: Receiver<FanotifyEvent>) {
-    for ev in rx.iter() {
-        let pid = ev.pid();
-        let name = pid_to_name(pid);
-        let mask = ev.mask();
-        let code = mask_to_code(mask);
+/// Escape a string for inclusion in a JSON string literal.
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
 
-        if let Some(fd) = ev.fd() {
-            let raw_fd = fd.as_raw_fd();
-            let path = fd_to_path(raw_fd)
-                .map(|p| p.display().to_string())
-                .unwrap_or_else(|_| "[unknown]".into());
+/// Milliseconds since the Unix epoch, or 0 if the clock is before it.
+fn epoch_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+/// Process resolved events from the channel, rendering them in the chosen
+/// output format.
+fn process_events(rx: Receiver<TracedEvent>, format: OutputFormat) {
+    for ev in rx.iter() {
+        let name = pid_to_name(ev.pid);
+        let code = mask_to_code(ev.mask);
+        let path = ev.path.unwrap_or_else(|| "[unknown]".into());
 
-            println!("{}({}): {:<3} {}", name, pid, code, path);
+        match format {
+            OutputFormat::Human => println!("{}({}): {:<3} {}", name, ev.pid, code, path),
+            OutputFormat::Json => println!(
+                r#"{{"ts":{},"comm":"{}","pid":{},"events":"{}","path":"{}"}}"#,
+                epoch_millis(ev.time),
+                json_escape(&name),
+                ev.pid,
+                json_escape(&code),
+                json_escape(&path),
+            ),
         }
     }
 }
 
+/// Open a directory fd suitable for `open_by_handle_at` on the given mount.
+fn open_mount_fd<P: AsRef<Path>>(path: P) -> nix::Result<OwnedFd> {
+    open(
+        path.as_ref(),
+        OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let fan = setup_fanotify()?;
-    let mounts = monitored_mounts();
+    let config = match Config::parse(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!(
+                "usage: fatrace-rs [--json] [--fs LIST] [--fs-add LIST] \
+                 [--policy] [--allow SPEC] [--deny SPEC] [--exclude PATH] [PATH...]"
+            );
+            return Ok(());
+        }
+    };
+
+    let mode = config.mode;
+    let rules = RuleSet::new(config.rules);
+    let exclude_paths = config.exclude;
+
+    let fan = Arc::new(setup_fanotify(mode)?);
 
-    if mounts.is_empty() {
-        eprintln!("No suitable mounts found to monitor.");
+    // Mark the explicit paths if given, otherwise every accepted mount.
+    let targets: Vec<String> = if config.paths.is_empty() {
+        monitored_mounts(&config.accepted_fs)
+            .into_iter()
+            .map(|(_dev, mount)| mount)
+            .collect()
+    } else {
+        config.paths.clone()
+    };
+
+    if targets.is_empty() {
+        eprintln!("No suitable paths found to monitor.");
         return Ok(());
     }
 
-    for (_dev, mount) in &mounts {
-        if let Err(e) = mark_mount(&fan, mount) {
-            eprintln!("Failed to mark {}: {}", mount, e);
+    let mut mount_fds = Vec::new();
+    for target in &targets {
+        if let Err(e) = mark_mount(&fan, target, mode) {
+            eprintln!("Failed to mark {}: {}", target, e);
+            continue;
+        }
+        // Keep an fd per target so file handles from FID events can be resolved.
+        // Only the Notify reader resolves handles; Policy mode uses the event's
+        // own fd, so skip the O_PATH opens there.
+        if mode == TraceMode::Notify {
+            match open_mount_fd(target) {
+                Ok(fd) => mount_fds.push(fd),
+                Err(e) => eprintln!("Failed to open {} for handle resolution: {}", target, e),
+            }
+        }
+    }
+
+    // Ignore marks are layered after the mount marks so they take effect on
+    // top of the already-watched mounts.
+    for path in &exclude_paths {
+        if let Err(e) = mark_ignore(&fan, path, mode) {
+            eprintln!("Failed to add ignore mark on {}: {}", path, e);
         }
     }
 
-    let (tx, rx) = bounded::<FanotifyEvent>(512);
-    spawn_reader(fan, tx);
-    process_events(rx);
+    let (tx, rx) = bounded::<TracedEvent>(512);
+    match mode {
+        TraceMode::Notify => spawn_reader_notify(Arc::clone(&fan), mount_fds, tx),
+        TraceMode::Policy => spawn_reader_policy(Arc::clone(&fan), tx, rules),
+    }
+    process_events(rx, config.format);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_to_code_reports_exec_distinctly_from_open() {
+        assert_eq!(mask_to_code(MaskFlags::FAN_OPEN), "O");
+        assert_eq!(mask_to_code(MaskFlags::FAN_OPEN_EXEC), "E");
+        // An exec-open is still an open, but must be reported as 'E' only.
+        assert_eq!(
+            mask_to_code(MaskFlags::FAN_OPEN_EXEC | MaskFlags::FAN_OPEN),
+            "E"
+        );
+    }
+
+    #[test]
+    fn mask_to_code_orders_and_combines_flags() {
+        assert_eq!(
+            mask_to_code(MaskFlags::FAN_OPEN | MaskFlags::FAN_ACCESS),
+            "OR"
+        );
+        assert_eq!(mask_to_code(MaskFlags::FAN_CLOSE_WRITE), "C");
+        assert_eq!(mask_to_code(MaskFlags::FAN_CLOSE_NOWRITE), "c");
+        assert_eq!(
+            mask_to_code(MaskFlags::FAN_MODIFY | MaskFlags::FAN_ONDIR),
+            "W/"
+        );
+    }
+
+    #[test]
+    fn mask_to_code_marks_unknown_masks() {
+        assert_eq!(mask_to_code(MaskFlags::empty()), "?");
+    }
+
+    #[test]
+    fn masks_split_dirent_events_away_from_the_mount() {
+        // Directory-entry/inode events must ride the filesystem mark, not the
+        // mount mark, or the kernel rejects the mount mark with EINVAL.
+        let dirent = MaskFlags::FAN_CREATE
+            | MaskFlags::FAN_DELETE
+            | MaskFlags::FAN_MOVED_FROM
+            | MaskFlags::FAN_MOVED_TO
+            | MaskFlags::FAN_ATTRIB;
+        assert!(!mount_event_mask(TraceMode::Notify).intersects(dirent));
+        assert!(filesystem_event_mask(TraceMode::Notify).contains(dirent));
+        // Policy mode watches permissions only, so it carries no filesystem mark.
+        assert!(filesystem_event_mask(TraceMode::Policy).is_empty());
+    }
+
+    /// Marking must actually succeed against the running kernel, which the
+    /// `mask_to_code_*` formatter tests cannot catch. Requires CAP_SYS_ADMIN,
+    /// so it is ignored by default; run with `cargo test -- --ignored` as root.
+    #[test]
+    #[ignore = "requires root / CAP_SYS_ADMIN"]
+    fn mark_mount_succeeds_on_running_kernel() {
+        let fan = setup_fanotify(TraceMode::Notify).expect("fanotify init");
+        mark_mount(&fan, "/", TraceMode::Notify).expect("mount + filesystem mark");
+    }
+
+    /// Build an info record: 1-byte type, 1 pad, 2-byte native-endian length,
+    /// then body.
+    fn info_record(info_type: u8, body: &[u8]) -> Vec<u8> {
+        let len = (4 + body.len()) as u16;
+        let mut rec = vec![info_type, 0];
+        rec.extend_from_slice(&len.to_ne_bytes());
+        rec.extend_from_slice(body);
+        rec
+    }
+
+    #[test]
+    fn fid_to_path_handles_empty_and_truncated_buffers() {
+        assert_eq!(fid_to_path(&[], &[]), None);
+        // Fewer than a header's worth of bytes must not panic.
+        assert_eq!(fid_to_path(&[1, 0, 8], &[]), None);
+    }
+
+    #[test]
+    fn fid_to_path_skips_non_fid_records() {
+        // A record with an unrelated info type is stepped over, not parsed.
+        let rec = info_record(200, &[0; 8]);
+        assert_eq!(fid_to_path(&rec, &[]), None);
+    }
+
+    #[test]
+    fn fid_to_path_breaks_on_bogus_length() {
+        // A declared length past the end of the buffer must stop iteration.
+        let data = [libc::FAN_EVENT_INFO_TYPE_FID, 0, 0xff, 0xff];
+        assert_eq!(fid_to_path(&data, &[]), None);
+    }
+
+    #[test]
+    fn resolve_fid_record_rejects_short_records() {
+        // Anything shorter than header + fsid + file_handle prefix is refused
+        // before any syscall is attempted.
+        assert_eq!(resolve_fid_record(&[0; 10], &[]), None);
+    }
+
+    fn args(list: &[&str]) -> Config {
+        Config::parse(list.iter().map(|s| s.to_string())).unwrap()
+    }
+
+    #[test]
+    fn glob_match_handles_exact_prefix_and_wildcards() {
+        assert!(glob_match("/etc/passwd", "/etc/passwd"));
+        assert!(!glob_match("/etc/passwd", "/etc/shadow"));
+        assert!(glob_match("/etc/*", "/etc/passwd"));
+        assert!(!glob_match("/etc/*", "/var/log"));
+        // A wildcard in the middle and several wildcards both work.
+        assert!(glob_match("/var/*/app.log", "/var/log/app.log"));
+        assert!(glob_match("*.log", "/var/log/app.log"));
+        assert!(glob_match("/a/*/*/d", "/a/b/c/d"));
+    }
+
+    #[test]
+    fn parse_rule_splits_optional_process_name() {
+        let any = parse_rule("/etc/*", Verdict::Deny);
+        assert_eq!(any.path_glob, "/etc/*");
+        assert!(any.proc_name.is_none());
+
+        let scoped = parse_rule("/etc/*@sshd", Verdict::Deny);
+        assert_eq!(scoped.path_glob, "/etc/*");
+        assert_eq!(scoped.proc_name.as_deref(), Some("sshd"));
+    }
+
+    #[test]
+    fn ruleset_first_match_wins_and_defaults_to_allow() {
+        let rules = RuleSet::new(vec![
+            parse_rule("/secret/*@curl", Verdict::Deny),
+            parse_rule("/secret/*", Verdict::Allow),
+        ]);
+        // Process-scoped deny fires only for the named process.
+        assert!(matches!(rules.evaluate("/secret/x", "curl"), Verdict::Deny));
+        assert!(matches!(rules.evaluate("/secret/x", "cat"), Verdict::Allow));
+        // Nothing matches -> allow.
+        assert!(matches!(rules.evaluate("/other", "curl"), Verdict::Allow));
+    }
+
+    #[test]
+    fn parse_enables_policy_when_rules_are_given() {
+        let cfg = args(&["--deny", "/secret/*@curl"]);
+        assert!(cfg.mode == TraceMode::Policy);
+        assert_eq!(cfg.rules.len(), 1);
+
+        let explicit = args(&["--policy"]);
+        assert!(explicit.mode == TraceMode::Policy);
+
+        // Plain invocation stays observational.
+        assert!(args(&[]).mode == TraceMode::Notify);
+    }
+
+    #[test]
+    fn json_escape_quotes_backslashes_and_controls() {
+        assert_eq!(json_escape("plain"), "plain");
+        assert_eq!(json_escape(r#"a"b"#), r#"a\"b"#);
+        assert_eq!(json_escape(r"a\b"), r"a\\b");
+        assert_eq!(json_escape("a\tb\nc\r"), "a\\tb\\nc\\r");
+        // Other control characters fall back to \u escapes; printable UTF-8
+        // passes through untouched.
+        assert_eq!(json_escape("\u{0007}"), "\\u0007");
+        assert_eq!(json_escape("café"), "café");
+    }
+}